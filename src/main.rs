@@ -1,3 +1,5 @@
+mod metrics;
+
 use anyhow::{anyhow, Context, Result};
 use bigdecimal::BigDecimal;
 use clap::Parser;
@@ -9,10 +11,75 @@ use sqlx::{
 };
 use web3::{
     transports::Http,
-    types::{BlockId, BlockNumber, TransactionReceipt, H256, U64},
+    types::{BlockId, BlockNumber, Transaction, TransactionId, TransactionReceipt, H256, U256, U64},
 };
 type Web3 = web3::Web3<Http>;
 
+/// The subset of a block's header we need to put a settlement's gas price in context.
+struct BlockInfo {
+    timestamp: DateTime<Utc>,
+    /// `None` for pre-London blocks, which have no EIP-1559 base fee.
+    base_fee_per_gas: Option<U256>,
+}
+
+/// Reward percentiles requested from `eth_feeHistory`, used to place a settlement's
+/// priority fee on the market's distribution of tips for its block.
+const REWARD_PERCENTILES: [f64; 5] = [10., 25., 50., 75., 90.];
+
+/// Per-block priority fee percentiles for a range of blocks, as returned by a single
+/// `eth_feeHistory` call. Cached so we don't make one RPC call per settlement.
+struct FeeHistoryTable {
+    oldest_block: i64,
+    /// `reward[i]` holds the fee paid at each of `REWARD_PERCENTILES` for block `oldest_block + i`.
+    reward: Vec<Vec<U256>>,
+}
+
+impl FeeHistoryTable {
+    fn rewards_for_block(&self, block_number: i64) -> Option<&[U256]> {
+        let index = usize::try_from(block_number - self.oldest_block).ok()?;
+        self.reward.get(index).map(|rewards| rewards.as_slice())
+    }
+}
+
+async fn fee_history_for_range(web3: &Web3, from: i64, to: i64) -> Option<FeeHistoryTable> {
+    let block_count = U64::from((to - from + 1) as u64);
+    let newest_block = BlockNumber::Number(U64::from(to as u64));
+    let history = match web3
+        .eth()
+        .fee_history(block_count, newest_block, Some(REWARD_PERCENTILES.to_vec()))
+        .await
+    {
+        Ok(history) => history,
+        Err(err) => {
+            eprintln!(
+                "warning: eth_feeHistory not supported by this node, skipping market gas price benchmark ({err})"
+            );
+            return None;
+        }
+    };
+    let Some(reward) = history.reward else {
+        eprintln!("warning: node did not return fee history rewards, skipping market gas price benchmark");
+        return None;
+    };
+    Some(FeeHistoryTable {
+        oldest_block: history.oldest_block.as_u64() as i64,
+        reward,
+    })
+}
+
+/// Where `priority_fee` falls on the `REWARD_PERCENTILES` curve for its block: the label of
+/// the highest percentile whose reward it reaches (e.g. "75th"), or "below 10th".
+fn priority_fee_percentile(priority_fee: f64, rewards: &[U256]) -> Option<&'static str> {
+    let labels = ["10th", "25th", "50th", "75th", "90th"];
+    rewards
+        .iter()
+        .zip(labels)
+        .filter(|(reward, _)| priority_fee >= reward.to_f64_lossy())
+        .map(|(_, label)| label)
+        .last()
+        .or(Some("below 10th"))
+}
+
 #[derive(Parser, Debug)]
 struct Args {
     /// URL of the ethereum node.
@@ -35,20 +102,45 @@ struct Args {
     /// This gets ignored if you pass --from.
     #[clap(long, env, default_value = "100")]
     blocks: i64,
+
+    /// Instead of analysing the block range once and exiting, keep running and tail new
+    /// settlements as blocks arrive, exposing the overpayment figures as Prometheus metrics.
+    #[clap(long, env)]
+    serve: bool,
+
+    /// Port the Prometheus metrics endpoint is served on in `--serve` mode.
+    #[clap(long, env, default_value = "9898")]
+    metrics_port: u16,
+
+    /// How to render the analysis: human-readable text, or structured json/csv for
+    /// downstream tooling.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
 }
 
-async fn timestamp_at_block(web3: &Web3, block: U64) -> Result<DateTime<Utc>> {
-    let timestamp: i64 = web3
+async fn block_info_at_block(web3: &Web3, block: U64) -> Result<BlockInfo> {
+    let block = web3
         .eth()
         .block(BlockId::Number(BlockNumber::Number(block.into())))
         .await
         .context("get current block")?
-        .ok_or_else(|| anyhow::anyhow!("block did not contain timestamp"))?
+        .ok_or_else(|| anyhow::anyhow!("block did not contain timestamp"))?;
+    let timestamp: i64 = block
         .timestamp
         .try_into()
         .map_err(|_| anyhow::anyhow!("can't convert timestamp to i64"))?;
     let naive = NaiveDateTime::from_timestamp(timestamp, 0);
-    Ok(DateTime::from_utc(naive, Utc))
+    Ok(BlockInfo {
+        timestamp: DateTime::from_utc(naive, Utc),
+        base_fee_per_gas: block.base_fee_per_gas,
+    })
 }
 
 #[tokio::main]
@@ -67,18 +159,28 @@ async fn main() -> Result<()> {
         .context("get current block")?
         .as_u64() as i64;
     let to = args.to.unwrap_or_else(|| {
-        println!("Supplied no end block; analysis will end at current block");
+        eprintln!("Supplied no end block; analysis will end at current block");
         current_block
     });
     let from = args.from.unwrap_or_else(|| {
-        println!(
+        eprintln!(
             "Supplied no start block; analysis will start {} blocks before end",
             args.blocks
         );
         to - args.blocks
     });
     anyhow::ensure!(from < to, "start has to be before end");
-    println!("Analysing settlements from block {from} to {to}\n");
+
+    if args.serve {
+        return serve_metrics(web3, connection, from, args.metrics_port).await;
+    }
+
+    let is_text = args.output == OutputFormat::Text;
+    if is_text {
+        println!("Analysing settlements from block {from} to {to}\n");
+    }
+
+    let fee_history = fee_history_for_range(&web3, from, to).await;
 
     let settlements: Vec<SettlementRow> = settlements(from, to, &mut connection)
         .try_collect()
@@ -86,41 +188,252 @@ async fn main() -> Result<()> {
         .context("get settlements from db")?;
     let mut over_payed_excess = 0.;
     let mut over_payed_total = 0.;
-    for settlement in settlements {
-        println!(
-            "settlement in tx {} in block {}",
-            Hex(&settlement.tx_hash),
-            settlement.block_number
-        );
-        let hash = H256(settlement.tx_hash.try_into().map_err(|_| anyhow!(""))?);
-        let (receipt, orders) = futures::join!(
+    let mut settlement_records = Vec::new();
+    let mut order_records = Vec::new();
+    for i in 0..settlements.len() {
+        let settlement = &settlements[i];
+        let next_log_index = settlements
+            .get(i + 1)
+            .filter(|next| next.block_number == settlement.block_number)
+            .map(|next| next.log_index);
+        if is_text {
+            println!(
+                "settlement in tx {} in block {}",
+                Hex(&settlement.tx_hash),
+                settlement.block_number
+            );
+        }
+        let tx_hash = Hex(&settlement.tx_hash).to_string();
+        let block_number = settlement.block_number;
+        let hash = H256(settlement.tx_hash.clone().try_into().map_err(|_| anyhow!(""))?);
+        let (receipt, transaction, orders) = futures::join!(
             web3.eth().transaction_receipt(hash),
-            orders(settlement.block_number, &mut connection).try_collect::<Vec<OrderRow>>()
+            web3.eth().transaction(TransactionId::Hash(hash)),
+            orders(settlement.block_number, settlement.log_index, next_log_index, &mut connection)
+                .try_collect::<Vec<OrderRow>>()
         );
         let receipt = match receipt.context("transaction_receipt")? {
             Some(receipt) => receipt,
             None => {
-                println!("transaction receipt not found");
+                if is_text {
+                    println!("transaction receipt not found");
+                }
+                continue;
+            }
+        };
+        let transaction = match transaction.context("transaction_by_hash")? {
+            Some(transaction) => transaction,
+            None => {
+                if is_text {
+                    println!("transaction not found");
+                }
                 continue;
             }
         };
         let orders = orders.context("orders")?;
         if orders.iter().any(|order| order.sell_token.is_none()) {
-            println!("order information not found (probably staging settlement)");
+            if is_text {
+                println!("order information not found (probably staging settlement)");
+            }
             continue;
         }
-        println!();
-        let analysis = print_settlement(&web3, &receipt, &orders).await;
-        over_payed_excess += analysis.0;
-        over_payed_total += analysis.1;
-        println!(
-            "\n--------------------------------------------------------------------------------\n"
-        );
+        if is_text {
+            println!();
+        }
+        let analysis = print_settlement(
+            &web3,
+            &receipt,
+            &transaction,
+            &orders,
+            fee_history.as_ref(),
+            args.output,
+        )
+        .await;
+        over_payed_excess += analysis.over_payed_excess;
+        over_payed_total += analysis.over_payed_total;
+        if is_text {
+            println!(
+                "\n--------------------------------------------------------------------------------\n"
+            );
+        }
+        settlement_records.push(SettlementRecord {
+            tx_hash,
+            block_number,
+            total_gas: analysis.total_gas,
+            total_gas_eth: analysis.total_gas_eth,
+            total_earned_fee_eth: analysis.total_earned_fee_eth,
+            total_unsubsidized_fee_eth: analysis.total_unsubsidized_fee_eth,
+            over_payed_excess: analysis.over_payed_excess,
+            over_payed_total: analysis.over_payed_total,
+            old_orders: analysis.old_orders,
+            recent_orders: analysis.recent_orders,
+        });
+        order_records.extend(analysis.orders);
+    }
+    let aggregate = AggregateRecord {
+        settlements_analyzed: settlement_records.len(),
+        over_payed_excess,
+        over_payed_total,
+    };
+    match args.output {
+        OutputFormat::Text => {
+            println!("over payed (excess of 2x) {over_payed_excess:.1e}, over payed (total) {over_payed_total:.1e}");
+        }
+        OutputFormat::Json => print_json(&order_records, &settlement_records, &aggregate)?,
+        OutputFormat::Csv => print_csv(&order_records, &settlement_records, &aggregate)?,
+    }
+    Ok(())
+}
+
+/// Prints one JSON object containing the per-order records, per-settlement records and the
+/// final aggregate as top-level arrays/fields.
+fn print_json(
+    orders: &[OrderRecord],
+    settlements: &[SettlementRecord],
+    aggregate: &AggregateRecord,
+) -> Result<()> {
+    #[derive(serde::Serialize)]
+    struct Document<'a> {
+        orders: &'a [OrderRecord],
+        settlements: &'a [SettlementRecord],
+        aggregate: &'a AggregateRecord,
     }
-    println!("over payed (excess of 2x) {over_payed_excess:.1e}, over payed (total) {over_payed_total:.1e}");
+    let document = Document { orders, settlements, aggregate };
+    println!("{}", serde_json::to_string_pretty(&document).context("serialize analysis as json")?);
     Ok(())
 }
 
+/// Prints three CSV tables in sequence (orders, settlements, aggregate), each with its own
+/// header row, separated by a blank line.
+fn print_csv(
+    orders: &[OrderRecord],
+    settlements: &[SettlementRecord],
+    aggregate: &AggregateRecord,
+) -> Result<()> {
+    // `csv::Writer` only writes the header lazily, before the first `serialize` call, so an
+    // empty `rows` slice would otherwise produce a headerless (and thus unidentifiable)
+    // table. Writing the header ourselves up front and disabling the writer's own header
+    // handling keeps a table's shape recognisable even when it has no rows.
+    fn write_table<T: serde::Serialize>(headers: &[&str], rows: &[T]) -> Result<String> {
+        let mut writer = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+        writer.write_record(headers).context("write csv header")?;
+        for row in rows {
+            writer.serialize(row).context("serialize csv row")?;
+        }
+        String::from_utf8(writer.into_inner().context("flush csv writer")?).context("csv output is not utf8")
+    }
+    const ORDER_HEADERS: [&str; 15] = [
+        "tx_hash",
+        "uid",
+        "sell_token",
+        "sell_token_price",
+        "earned_fee",
+        "earned_fee_eth",
+        "unsubsidized_fee",
+        "unsubsidized_fee_eth",
+        "gas_amount",
+        "gas_price",
+        "effective_gas_price",
+        "order_age_seconds",
+        "is_old",
+        "over_payed_excess",
+        "over_payed_total",
+    ];
+    const SETTLEMENT_HEADERS: [&str; 10] = [
+        "tx_hash",
+        "block_number",
+        "total_gas",
+        "total_gas_eth",
+        "total_earned_fee_eth",
+        "total_unsubsidized_fee_eth",
+        "over_payed_excess",
+        "over_payed_total",
+        "old_orders",
+        "recent_orders",
+    ];
+    const AGGREGATE_HEADERS: [&str; 3] =
+        ["settlements_analyzed", "over_payed_excess", "over_payed_total"];
+    print!("{}", write_table(&ORDER_HEADERS, orders)?);
+    println!();
+    print!("{}", write_table(&SETTLEMENT_HEADERS, settlements)?);
+    println!();
+    print!("{}", write_table(&AGGREGATE_HEADERS, std::slice::from_ref(aggregate))?);
+    Ok(())
+}
+
+/// How often the daemon checks for new blocks in `--serve` mode.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Long-running mode: tails new settlements as blocks arrive and exposes their overpayment
+/// figures as Prometheus metrics on `http://0.0.0.0:<metrics_port>/metrics` instead of
+/// printing once and exiting.
+async fn serve_metrics(
+    web3: Web3,
+    mut connection: PgConnection,
+    from: i64,
+    metrics_port: u16,
+) -> Result<()> {
+    let metrics: &'static metrics::Metrics =
+        Box::leak(Box::new(metrics::Metrics::new().context("set up metrics registry")?));
+    tokio::spawn(metrics::serve(metrics_port, metrics));
+    println!("serving metrics on http://0.0.0.0:{metrics_port}/metrics");
+
+    let mut next_block = from;
+    loop {
+        let current_block = web3
+            .eth()
+            .block_number()
+            .await
+            .context("get current block")?
+            .as_u64() as i64;
+        if next_block <= current_block {
+            println!("tailing settlements from block {next_block} to {current_block}");
+            let fee_history = fee_history_for_range(&web3, next_block, current_block).await;
+            let settlements: Vec<SettlementRow> =
+                settlements(next_block, current_block, &mut connection)
+                    .try_collect()
+                    .await
+                    .context("get settlements from db")?;
+            for i in 0..settlements.len() {
+                let settlement = &settlements[i];
+                let next_log_index = settlements
+                    .get(i + 1)
+                    .filter(|next| next.block_number == settlement.block_number)
+                    .map(|next| next.log_index);
+                let solver = Hex(&settlement.solver).to_string();
+                let hash = H256(settlement.tx_hash.clone().try_into().map_err(|_| anyhow!(""))?);
+                let (receipt, transaction, orders) = futures::join!(
+                    web3.eth().transaction_receipt(hash),
+                    web3.eth().transaction(TransactionId::Hash(hash)),
+                    orders(settlement.block_number, settlement.log_index, next_log_index, &mut connection)
+                        .try_collect::<Vec<OrderRow>>()
+                );
+                let (Some(receipt), Some(transaction)) =
+                    (receipt.context("transaction_receipt")?, transaction.context("transaction_by_hash")?)
+                else {
+                    continue;
+                };
+                let orders = orders.context("orders")?;
+                if orders.iter().any(|order| order.sell_token.is_none()) {
+                    continue;
+                }
+                let analysis = print_settlement(
+                    &web3,
+                    &receipt,
+                    &transaction,
+                    &orders,
+                    fee_history.as_ref(),
+                    OutputFormat::Text,
+                )
+                .await;
+                metrics.observe_settlement(&solver, &analysis);
+            }
+            next_block = current_block + 1;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
 struct Hex<'a>(&'a [u8]);
 impl<'a> std::fmt::Display for Hex<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -146,20 +459,98 @@ struct OrderRow {
     creation_timestamp: Option<DateTime<Utc>>,
 }
 
+/// Everything `print_settlement` computes about a single settlement, for consumers other
+/// than the human-readable log (metrics export, structured output).
+struct SettlementAnalysis {
+    over_payed_excess: f64,
+    over_payed_total: f64,
+    total_gas: f64,
+    total_gas_eth: f64,
+    total_earned_fee_eth: f64,
+    total_unsubsidized_fee_eth: f64,
+    old_orders: u64,
+    recent_orders: u64,
+    orders: Vec<OrderRecord>,
+}
+
+/// Structured, serializable record of a single order's contribution to a settlement, for
+/// `--output json`/`--output csv`.
+#[derive(serde::Serialize)]
+struct OrderRecord {
+    tx_hash: String,
+    uid: String,
+    sell_token: String,
+    sell_token_price: f64,
+    earned_fee: f64,
+    earned_fee_eth: f64,
+    unsubsidized_fee: f64,
+    unsubsidized_fee_eth: f64,
+    gas_amount: f64,
+    gas_price: f64,
+    effective_gas_price: f64,
+    order_age_seconds: i64,
+    is_old: bool,
+    over_payed_excess: f64,
+    over_payed_total: f64,
+}
+
+/// Structured, serializable record of a whole settlement, for `--output json`/`--output csv`.
+#[derive(serde::Serialize)]
+struct SettlementRecord {
+    tx_hash: String,
+    block_number: i64,
+    total_gas: f64,
+    total_gas_eth: f64,
+    total_earned_fee_eth: f64,
+    total_unsubsidized_fee_eth: f64,
+    over_payed_excess: f64,
+    over_payed_total: f64,
+    old_orders: u64,
+    recent_orders: u64,
+}
+
+/// Final, aggregate record across every settlement analyzed, for `--output json`/`--output csv`.
+#[derive(serde::Serialize)]
+struct AggregateRecord {
+    settlements_analyzed: usize,
+    over_payed_excess: f64,
+    over_payed_total: f64,
+}
+
 async fn print_settlement(
     web3: &Web3,
     receipt: &TransactionReceipt,
+    transaction: &Transaction,
     orders: &[OrderRow],
-) -> (f64, f64) {
-    let settlement_timestamp = timestamp_at_block(web3, receipt.block_number.unwrap())
+    fee_history: Option<&FeeHistoryTable>,
+    output: OutputFormat,
+) -> SettlementAnalysis {
+    let tx_hash = Hex(receipt.transaction_hash.as_bytes()).to_string();
+    let block_info = block_info_at_block(web3, receipt.block_number.unwrap())
         .await
         .unwrap();
+    let settlement_timestamp = block_info.timestamp;
+    let effective_gas_price = receipt.effective_gas_price.unwrap().to_f64_lossy();
+    // On post-London chains the base fee is burned and entirely outside the solver's
+    // control, so only the priority fee (the tip actually paid to the proposer) should
+    // count towards "overpayment". Pre-London blocks have no base fee to subtract.
+    let (base_fee_per_gas, priority_fee) = match block_info.base_fee_per_gas {
+        Some(base_fee_per_gas) => {
+            let base_fee_per_gas = base_fee_per_gas.to_f64_lossy();
+            (base_fee_per_gas, effective_gas_price - base_fee_per_gas)
+        }
+        None => (0., effective_gas_price),
+    };
+    let is_text = output == OutputFormat::Text;
     let mut total_gas = 0.;
     let mut total_gas_eth = 0.;
     let mut total_earned_fee_eth = 0.;
     let mut total_unsubsidized_fee_eth = 0.;
     let mut over_payed_excess = 0.;
     let mut over_payed_total = 0.;
+    let mut old_orders = 0;
+    let mut recent_orders = 0;
+    let mut order_records = Vec::with_capacity(orders.len());
     for order in orders {
         let uid = Hex(&order.uid);
         let sell_token = Hex(order.sell_token.as_ref().unwrap());
@@ -178,57 +569,135 @@ async fn print_settlement(
                 .unwrap_or_default();
         let is_old = order_age > 20 * 60;
         let age = if is_old { "old" } else { "recent" };
-        let gas_price_intolerated_difference =
-            receipt.effective_gas_price.unwrap().to_f64_lossy() - gas_price * 2.;
-        let gas_price_excess = receipt.effective_gas_price.unwrap().to_f64_lossy() - gas_price;
-        println!(
-            "\
-            order {uid}, sell_token {sell_token}, sell_token_price {sell_token_price:.1e}, \
-            earned fee {earned_fee:.1e} ({earned_fee_eth:.1e} eth), \
-            unsubsidized fee {unsubsidized_fee:.1e} ({unsubsidized_fee_eth:.1e} eth) \
-            gas {gas:.1e} at price {gas_price:.1e} for a total of {gas_eth:.1e} eth \
-            age {age} \
-            ",
-        );
+        if is_old {
+            old_orders += 1;
+        } else {
+            recent_orders += 1;
+        }
+        let gas_price_intolerated_difference = priority_fee - gas_price * 2.;
+        let gas_price_excess = priority_fee - gas_price;
+        if is_text {
+            println!(
+                "\
+                order {uid}, sell_token {sell_token}, sell_token_price {sell_token_price:.1e}, \
+                earned fee {earned_fee:.1e} ({earned_fee_eth:.1e} eth), \
+                unsubsidized fee {unsubsidized_fee:.1e} ({unsubsidized_fee_eth:.1e} eth) \
+                gas {gas:.1e} at price {gas_price:.1e} for a total of {gas_eth:.1e} eth \
+                age {age} \
+                ",
+            );
+        }
+        let mut order_over_payed_excess = 0.;
+        let mut order_over_payed_total = 0.;
         if gas_price_intolerated_difference > 0. {
-            let over_payed_excessive = gas_price_intolerated_difference * gas / 1e18;
-            let over_payed = gas_price_excess * gas / 1e18;
-            over_payed_excess += over_payed_excessive;
-            over_payed_total += over_payed;
-            println!("over payed (excess of 2x) {over_payed_excess:.1e} over payed (total) {over_payed:.1e}");
+            order_over_payed_excess = gas_price_intolerated_difference * gas / 1e18;
+            order_over_payed_total = gas_price_excess * gas / 1e18;
+            over_payed_excess += order_over_payed_excess;
+            over_payed_total += order_over_payed_total;
+            if is_text {
+                println!("over payed (excess of 2x) {over_payed_excess:.1e} over payed (total) {order_over_payed_total:.1e}");
+            }
         }
+        order_records.push(OrderRecord {
+            tx_hash: tx_hash.clone(),
+            uid: uid.to_string(),
+            sell_token: sell_token.to_string(),
+            sell_token_price,
+            earned_fee,
+            earned_fee_eth,
+            unsubsidized_fee,
+            unsubsidized_fee_eth,
+            gas_amount: gas,
+            gas_price,
+            effective_gas_price,
+            order_age_seconds: order_age,
+            is_old,
+            over_payed_excess: order_over_payed_excess,
+            over_payed_total: order_over_payed_total,
+        });
         total_gas += gas;
         total_gas_eth += gas_eth;
         total_earned_fee_eth += earned_fee_eth;
         total_unsubsidized_fee_eth += unsubsidized_fee_eth;
     }
-    println!();
-    println!("\
-        expected from orders:\n\
-        {total_gas:.1e} gas for {total_gas_eth:.1e} eth, \
-        earning fees {total_earned_fee_eth:.1e} eth (unsubsidized {total_unsubsidized_fee_eth:.1e} eth)\n\
-        ");
     let gas = receipt.gas_used.unwrap().to_f64_lossy();
-    let gas_price = receipt.effective_gas_price.unwrap().to_f64_lossy();
-    let gas_eth = gas * gas_price / 1e18;
-    println!(
-        "\
-        transaction actually executed with:\n\
-        {gas:.1e} gas for {gas_eth:.1e} eth (price {gas_price:.1e})\
-        ",
-    );
-    if over_payed_excess > 0.0 {
-        println!("over payed (excess of 2x) {over_payed_excess:.1e}, over payed (total) {over_payed_total:.1e}");
+    let gas_eth = gas * effective_gas_price / 1e18;
+    if is_text {
+        println!();
+        println!("\
+            expected from orders:\n\
+            {total_gas:.1e} gas for {total_gas_eth:.1e} eth, \
+            earning fees {total_earned_fee_eth:.1e} eth (unsubsidized {total_unsubsidized_fee_eth:.1e} eth)\n\
+            ");
+        println!(
+            "\
+            transaction actually executed with:\n\
+            {gas:.1e} gas for {gas_eth:.1e} eth (price {effective_gas_price:.1e}), \
+            of which priority fee {priority_fee:.1e}\
+            ",
+        );
+        if block_info.base_fee_per_gas.is_some() {
+            let base_fee_eth = gas * base_fee_per_gas / 1e18;
+            println!("base fee (unavoidable burn) {base_fee_eth:.1e} eth (price {base_fee_per_gas:.1e})");
+        }
+        if let Some(rewards) = fee_history.and_then(|table| {
+            table.rewards_for_block(receipt.block_number.unwrap().as_u64() as i64)
+        }) {
+            if let Some(percentile) = priority_fee_percentile(priority_fee, rewards) {
+                println!("tip was at the {percentile} percentile of priority fees paid in this block");
+            }
+        }
+        match transaction.max_fee_per_gas.zip(transaction.max_priority_fee_per_gas) {
+            Some((max_fee_per_gas, max_priority_fee_per_gas)) => {
+                let max_fee_per_gas = max_fee_per_gas.to_f64_lossy();
+                let max_priority_fee_per_gas = max_priority_fee_per_gas.to_f64_lossy();
+                let headroom = max_fee_per_gas - effective_gas_price;
+                // max_fee_per_gas_capped tracks whether the transaction's overall fee cap was
+                // the binding constraint (e.g. base fee spiked into it), while tip_capped tracks
+                // whether the solver's own priority fee cap was binding. Solvers commonly pad
+                // max_fee_per_gas well above base_fee + intended_tip as a safety margin, so these
+                // two can and do disagree: report both rather than conflating one into the other.
+                let max_fee_per_gas_capped = headroom <= 1.0;
+                let tip_capped = priority_fee >= max_priority_fee_per_gas - 1.0;
+                println!(
+                    "fee cap headroom {headroom:.1e} (max_fee_per_gas {max_fee_per_gas:.1e} minus effective gas price), \
+                    {} by max_fee_per_gas; \
+                    tip was {} by max_priority_fee_per_gas (cap {max_priority_fee_per_gas:.1e})",
+                    if max_fee_per_gas_capped { "capped" } else { "not capped" },
+                    if tip_capped { "capped" } else { "not capped" },
+                );
+            }
+            None => {
+                let kind = match transaction.transaction_type.map(|t| t.as_u64()) {
+                    Some(1) => "EIP-2930",
+                    _ => "legacy",
+                };
+                println!("{kind} transaction (type {:?}), no fee cap to report", transaction.transaction_type);
+            }
+        }
+        if over_payed_excess > 0.0 {
+            println!("over payed (excess of 2x) {over_payed_excess:.1e}, over payed (total) {over_payed_total:.1e}");
+        }
+    }
+    SettlementAnalysis {
+        over_payed_excess,
+        over_payed_total,
+        total_gas,
+        total_gas_eth,
+        total_earned_fee_eth,
+        total_unsubsidized_fee_eth,
+        old_orders,
+        recent_orders,
+        orders: order_records,
     }
-    (over_payed_excess, over_payed_total)
 }
 
 #[derive(sqlx::FromRow)]
 struct SettlementRow {
     tx_hash: Vec<u8>,
     block_number: i64,
-    #[allow(dead_code)]
     log_index: i64,
+    solver: Vec<u8>,
 }
 
 fn settlements(
@@ -238,7 +707,7 @@ fn settlements(
 ) -> BoxStream<'_, Result<SettlementRow, sqlx::Error>> {
     sqlx::query_as(
         "
-SELECT tx_hash, block_number, log_index
+SELECT tx_hash, block_number, log_index, solver
 FROM settlements
 WHERE block_number BETWEEN $1 AND $2
 ORDER BY (block_number, log_index) ASC
@@ -249,9 +718,13 @@ ORDER BY (block_number, log_index) ASC
     .fetch(connection)
 }
 
-// For simplicity nod handling multiple settlements in same block properly.
+/// Trades for a settlement live in the log range right after its own log (`log_index`) and
+/// before the next settlement's log in the same block, if any (`next_log_index`). Bounding
+/// by that range is what lets two settlements sharing a block each get only their own trades.
 fn orders(
     settlement_block: i64,
+    log_index: i64,
+    next_log_index: Option<i64>,
     connection: &mut PgConnection,
 ) -> BoxStream<'_, Result<OrderRow, sqlx::Error>> {
     let query = "
@@ -263,6 +736,8 @@ FROM (
     SELECT order_uid as uid, SUM(fee_amount) as sum_fee
     FROM trades t
     WHERE block_number = $1
+    AND log_index > $2
+    AND ($3::bigint IS NULL OR log_index < $3)
     GROUP BY uid
 ) AS t
 LEFT OUTER JOIN orders o ON o.uid = t.uid
@@ -270,5 +745,7 @@ LEFT OUTER JOIN order_fee_parameters f ON f.order_uid = t.uid
 ;";
     sqlx::query_as(query)
         .bind(settlement_block)
+        .bind(log_index)
+        .bind(next_log_index)
         .fetch(connection)
 }
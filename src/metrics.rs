@@ -0,0 +1,152 @@
+//! Prometheus metrics export for `--serve` mode. Holds one counter family per overpayment
+//! figure `print_settlement` computes, labelled by solver address.
+//!
+//! Labelling by solver (a small, stable set of addresses) rather than by settlement tx hash
+//! keeps series cardinality bounded in this long-running daemon: a per-tx-hash label would
+//! add a brand-new, never-removed series for every settlement for as long as the process runs.
+
+use crate::SettlementAnalysis;
+use anyhow::{Context, Result};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use prometheus::{CounterVec, Encoder, IntCounterVec, Opts, Registry, TextEncoder};
+use std::{convert::Infallible, net::SocketAddr};
+
+pub struct Metrics {
+    registry: Registry,
+    over_payed_excess: CounterVec,
+    over_payed_total: CounterVec,
+    gas_used: CounterVec,
+    earned_fee_eth: CounterVec,
+    unsubsidized_fee_eth: CounterVec,
+    old_orders: IntCounterVec,
+    recent_orders: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let over_payed_excess = CounterVec::new(
+            Opts::new(
+                "settlement_over_payed_excess_eth_total",
+                "Eth paid as priority fee in excess of 2x the order's quoted gas price, summed per solver.",
+            ),
+            &["solver"],
+        )?;
+        let over_payed_total = CounterVec::new(
+            Opts::new(
+                "settlement_over_payed_total_eth_total",
+                "Total eth paid as priority fee above the order's quoted gas price, summed per solver.",
+            ),
+            &["solver"],
+        )?;
+        let gas_used = CounterVec::new(
+            Opts::new(
+                "settlement_gas_used_total",
+                "Gas expected from the quoted gas amounts of a solver's settled orders.",
+            ),
+            &["solver"],
+        )?;
+        let earned_fee_eth = CounterVec::new(
+            Opts::new(
+                "settlement_earned_fee_eth_total",
+                "Subsidized fees earned from a solver's settled orders, in eth.",
+            ),
+            &["solver"],
+        )?;
+        let unsubsidized_fee_eth = CounterVec::new(
+            Opts::new(
+                "settlement_unsubsidized_fee_eth_total",
+                "Unsubsidized fees owed by a solver's settled orders, in eth.",
+            ),
+            &["solver"],
+        )?;
+        let old_orders = IntCounterVec::new(
+            Opts::new(
+                "settlement_old_orders_total",
+                "Orders older than 20 minutes included in a solver's settlements.",
+            ),
+            &["solver"],
+        )?;
+        let recent_orders = IntCounterVec::new(
+            Opts::new(
+                "settlement_recent_orders_total",
+                "Orders settled within 20 minutes of creation, included in a solver's settlements.",
+            ),
+            &["solver"],
+        )?;
+
+        registry.register(Box::new(over_payed_excess.clone())).context("register collector")?;
+        registry.register(Box::new(over_payed_total.clone())).context("register collector")?;
+        registry.register(Box::new(gas_used.clone())).context("register collector")?;
+        registry.register(Box::new(earned_fee_eth.clone())).context("register collector")?;
+        registry
+            .register(Box::new(unsubsidized_fee_eth.clone()))
+            .context("register collector")?;
+        registry.register(Box::new(old_orders.clone())).context("register collector")?;
+        registry.register(Box::new(recent_orders.clone())).context("register collector")?;
+
+        Ok(Self {
+            registry,
+            over_payed_excess,
+            over_payed_total,
+            gas_used,
+            earned_fee_eth,
+            unsubsidized_fee_eth,
+            old_orders,
+            recent_orders,
+        })
+    }
+
+    pub fn observe_settlement(&self, solver: &str, analysis: &SettlementAnalysis) {
+        self.over_payed_excess
+            .with_label_values(&[solver])
+            .inc_by(analysis.over_payed_excess);
+        self.over_payed_total
+            .with_label_values(&[solver])
+            .inc_by(analysis.over_payed_total);
+        self.gas_used.with_label_values(&[solver]).inc_by(analysis.total_gas);
+        self.earned_fee_eth
+            .with_label_values(&[solver])
+            .inc_by(analysis.total_earned_fee_eth);
+        self.unsubsidized_fee_eth
+            .with_label_values(&[solver])
+            .inc_by(analysis.total_unsubsidized_fee_eth);
+        self.old_orders
+            .with_label_values(&[solver])
+            .inc_by(analysis.old_orders);
+        self.recent_orders
+            .with_label_values(&[solver])
+            .inc_by(analysis.recent_orders);
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding prometheus metrics cannot fail");
+        buffer
+    }
+}
+
+/// Serves the metrics registry's current state as `GET /metrics` until the process exits.
+pub async fn serve(port: u16, metrics: &'static Metrics) -> Result<()> {
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+            let response = if req.uri().path() == "/metrics" {
+                Response::builder()
+                    .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(Body::from(metrics.encode()))
+                    .unwrap()
+            } else {
+                Response::builder().status(404).body(Body::empty()).unwrap()
+            };
+            Ok::<_, Infallible>(response)
+        }))
+    });
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    Server::bind(&addr).serve(make_service).await.context("metrics server")
+}